@@ -1,46 +1,279 @@
+use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::{lookup_host, TcpListener, TcpStream};
 
+use hyper::header::{HeaderMap, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE};
 use hyper::server::conn::http1;
 use hyper::service::service_fn;
 use hyper::{Method, Request, Response, StatusCode, Uri};
 use hyper_util::rt::TokioIo;
-use http_body_util::{combinators::BoxBody, BodyExt, Empty, Full};
-use hyper::body::Bytes;
+use http_body_util::{combinators::BoxBody, BodyExt, Empty, Full, StreamBody};
+use hyper::body::{Bytes, Frame};
 
-use clap::Parser;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio_util::io::{ReaderStream, StreamReader};
+
+use async_compression::tokio::bufread::{BrotliDecoder, BrotliEncoder, GzipDecoder, GzipEncoder};
+use futures_util::StreamExt;
+
+use clap::{Parser, ValueEnum};
 use base64::{Engine as _, engine::general_purpose};
 
-/// A robust HTTP proxy that requires basic authentication.
+/// The error type carried by every response body this proxy produces, so that
+/// compressed/decompressed streaming bodies can sit alongside the static ones.
+type BodyError = Box<dyn std::error::Error + Send + Sync>;
+type ResBody = BoxBody<Bytes, BodyError>;
+
+/// A robust HTTP proxy that requires authentication.
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     #[arg(short, long, default_value_t = 8080)]
     port: u16,
-    #[arg(short, long, required = true)]
-    username: String,
-    #[arg(long, required = true)]
-    password: String,
+    #[arg(short, long, requires = "password", conflicts_with = "token")]
+    username: Option<String>,
+    #[arg(long, requires = "username", conflicts_with = "token")]
+    password: Option<String>,
+    /// Bearer token to accept instead of a username/password pair.
+    #[arg(long, conflicts_with_all = ["username", "password"])]
+    token: Option<String>,
+    /// Prepend a PROXY protocol header to upstream connections so the origin
+    /// sees the real client address.
+    #[arg(long, value_enum)]
+    send_proxy_protocol: Option<ProxyProtocolVersion>,
+    /// Forward all traffic through another HTTP proxy instead of connecting
+    /// directly to origins.
+    #[arg(long, value_name = "HOST:PORT")]
+    upstream_proxy: Option<String>,
+    /// Value of the `Proxy-Authorization` header to send to --upstream-proxy.
+    #[arg(long, requires = "upstream_proxy")]
+    upstream_proxy_auth: Option<String>,
+    /// Allow requests to hosts matching this glob (repeatable). If any --allow
+    /// is given, only matching hosts are permitted.
+    #[arg(long = "allow")]
+    allow: Vec<String>,
+    /// Reject requests to hosts matching this glob (repeatable); evaluated
+    /// before --allow.
+    #[arg(long = "deny")]
+    deny: Vec<String>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+enum ProxyProtocolVersion {
+    V1,
+    V2,
+}
+
+/// A parent HTTP proxy this proxy chains through instead of connecting directly.
+struct UpstreamProxy {
+    addr: String,
+    auth: Option<String>,
+}
+
+/// Which hosts this proxy is willing to connect to.
+struct HostPolicy {
+    allow: Vec<String>,
+    deny: Vec<String>,
+}
+
+impl HostPolicy {
+    fn from_args(args: &Args) -> Self {
+        HostPolicy {
+            allow: args.allow.clone(),
+            deny: args.deny.clone(),
+        }
+    }
+
+    fn is_allowed(&self, host: &str) -> bool {
+        if self.deny.iter().any(|pattern| glob_match(pattern, host)) {
+            return false;
+        }
+        if self.allow.is_empty() {
+            return true;
+        }
+        self.allow.iter().any(|pattern| glob_match(pattern, host))
+    }
+}
+
+/// Matches `text` against a shell-style glob where `*` matches any sequence of
+/// characters and `?` matches exactly one; matching is case-insensitive.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let (mut star, mut matched) = (None, 0);
+
+    while ti < text.len() {
+        if pi < pattern.len() && (pattern[pi] == '?' || pattern[pi].eq_ignore_ascii_case(&text[ti])) {
+            pi += 1;
+            ti += 1;
+        } else if pi < pattern.len() && pattern[pi] == '*' {
+            star = Some(pi);
+            matched = ti;
+            pi += 1;
+        } else if let Some(star_idx) = star {
+            pi = star_idx + 1;
+            matched += 1;
+            ti = matched;
+        } else {
+            return false;
+        }
+    }
+    while pi < pattern.len() && pattern[pi] == '*' {
+        pi += 1;
+    }
+    pi == pattern.len()
+}
+
+/// Strips a trailing `:port` from a `host:port` or bare-host string.
+fn host_only(host_port: &str) -> &str {
+    host_port.rsplit_once(':').map_or(host_port, |(host, _)| host)
+}
+
+/// Credentials the proxy will accept on the `Proxy-Authorization` header.
+enum AuthCredentials {
+    Basic { username: String, password: String },
+    Bearer { token: String },
+}
+
+impl AuthCredentials {
+    fn from_args(args: &Args) -> Self {
+        match (&args.username, &args.password, &args.token) {
+            (Some(username), Some(password), None) => AuthCredentials::Basic {
+                username: username.clone(),
+                password: password.clone(),
+            },
+            (None, None, Some(token)) => AuthCredentials::Bearer {
+                token: token.clone(),
+            },
+            _ => {
+                eprintln!("Specify either --username/--password or --token");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    fn challenge(&self) -> &'static str {
+        match self {
+            AuthCredentials::Basic { .. } => "Basic realm=\"Proxy\"",
+            AuthCredentials::Bearer { .. } => "Bearer realm=\"Proxy\"",
+        }
+    }
+}
+
+/// Compares two byte strings in constant time, regardless of where they first differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Bounded LRU cache of `host:port` -> resolved `SocketAddr`, with a TTL per entry.
+struct DnsCache {
+    entries: HashMap<String, (SocketAddr, Instant)>,
+    order: VecDeque<String>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl DnsCache {
+    fn new(capacity: usize, ttl: Duration) -> Self {
+        DnsCache {
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            capacity,
+            ttl,
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<SocketAddr> {
+        let (addr, inserted) = *self.entries.get(key)?;
+        if inserted.elapsed() > self.ttl {
+            self.entries.remove(key);
+            self.order.retain(|k| k != key);
+            return None;
+        }
+        // Move this key to the back so `insert`'s eviction pops the true
+        // least-recently-used entry rather than the least-recently-inserted one.
+        if let Some(pos) = self.order.iter().position(|k| k == key) {
+            let key = self.order.remove(pos).unwrap();
+            self.order.push_back(key);
+        }
+        Some(addr)
+    }
+
+    fn insert(&mut self, key: String, addr: SocketAddr) {
+        if self.entries.insert(key.clone(), (addr, Instant::now())).is_none() {
+            self.order.push_back(key);
+        }
+        while self.entries.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+type DnsCacheHandle = Arc<Mutex<DnsCache>>;
+
+const DNS_CACHE_CAPACITY: usize = 1024;
+const DNS_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// Resolves `host:port` through the shared cache, populating it on a miss.
+async fn resolve_cached(cache: &DnsCacheHandle, host_port: &str) -> std::io::Result<SocketAddr> {
+    if let Some(addr) = cache.lock().unwrap().get(host_port) {
+        return Ok(addr);
+    }
+
+    let addr = lookup_host(host_port)
+        .await?
+        .next()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no addresses found"))?;
+
+    cache.lock().unwrap().insert(host_port.to_string(), addr);
+    Ok(addr)
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
     let addr = SocketAddr::from(([0, 0, 0, 0], args.port));
-    let credentials = Arc::new((args.username, args.password));
+    let credentials = Arc::new(AuthCredentials::from_args(&args));
+    let dns_cache: DnsCacheHandle = Arc::new(Mutex::new(DnsCache::new(DNS_CACHE_CAPACITY, DNS_CACHE_TTL)));
+    let proxy_protocol = args.send_proxy_protocol;
+    let host_policy = Arc::new(HostPolicy::from_args(&args));
+    let upstream_proxy = Arc::new(args.upstream_proxy.map(|addr| UpstreamProxy {
+        addr,
+        auth: args.upstream_proxy_auth,
+    }));
     let listener = TcpListener::bind(addr).await?;
     println!("HTTP proxy listening on {}, authentication is required.", addr);
 
     loop {
-        let (stream, _) = listener.accept().await?;
+        let (stream, client_addr) = listener.accept().await?;
         let io = TokioIo::new(stream);
         let creds = credentials.clone();
-        
+        let dns_cache = dns_cache.clone();
+        let upstream_proxy = upstream_proxy.clone();
+        let host_policy = host_policy.clone();
+
         tokio::task::spawn(async move {
             let service = service_fn(move |req| {
-                proxy(req, creds.clone())
+                proxy(
+                    req,
+                    creds.clone(),
+                    dns_cache.clone(),
+                    client_addr,
+                    proxy_protocol,
+                    upstream_proxy.clone(),
+                    host_policy.clone(),
+                )
             });
 
             if let Err(err) = http1::Builder::new()
@@ -56,27 +289,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
 async fn proxy(
     req: Request<hyper::body::Incoming>,
-    credentials: Arc<(String, String)>,
-) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
-    
+    credentials: Arc<AuthCredentials>,
+    dns_cache: DnsCacheHandle,
+    client_addr: SocketAddr,
+    proxy_protocol: Option<ProxyProtocolVersion>,
+    upstream_proxy: Arc<Option<UpstreamProxy>>,
+    host_policy: Arc<HostPolicy>,
+) -> Result<Response<ResBody>, hyper::Error> {
+
     // --- Authentication ---
-    let (user, pass) = &*credentials;
-    if req.headers().get("proxy-authorization").and_then(|h| h.to_str().ok())
-        .and_then(|v| v.strip_prefix("Basic "))
-        .and_then(|encoded| general_purpose::STANDARD.decode(encoded).ok())
-        .and_then(|decoded| String::from_utf8(decoded).ok())
-        .map_or(false, |decoded_str| {
-            let mut parts = decoded_str.splitn(2, ':');
-            if let (Some(req_user), Some(req_pass)) = (parts.next(), parts.next()) {
-                req_user == user && req_pass == pass
-            } else {
-                false
-            }
-        })
-    == false {
+    if !is_authenticated(&req, &credentials) {
         let mut res = Response::new(full_body("407 Proxy Authentication Required"));
         *res.status_mut() = StatusCode::PROXY_AUTHENTICATION_REQUIRED;
-        res.headers_mut().insert("Proxy-Authenticate", "Basic realm=\"Proxy\"".parse().unwrap());
+        res.headers_mut()
+            .insert("Proxy-Authenticate", credentials.challenge().parse().unwrap());
         return Ok(res);
     }
 
@@ -84,10 +310,24 @@ async fn proxy(
     if Method::CONNECT == req.method() {
         // Handle CONNECT for HTTPS tunneling
         if let Some(addr) = host_addr(req.uri()) {
+            if !host_policy.is_allowed(host_only(&addr)) {
+                eprintln!("CONNECT to {} rejected by host policy", addr);
+                return Ok(forbidden());
+            }
             tokio::task::spawn(async move {
                 match hyper::upgrade::on(req).await {
                     Ok(upgraded) => {
-                        if let Err(e) = tunnel(upgraded, addr).await {
+                        if let Err(e) = tunnel(
+                            upgraded,
+                            addr,
+                            dns_cache,
+                            client_addr,
+                            proxy_protocol,
+                            upstream_proxy,
+                            host_policy,
+                        )
+                        .await
+                        {
                             eprintln!("server io error: {}", e);
                         };
                     }
@@ -106,7 +346,54 @@ async fn proxy(
         let host = req.uri().host().expect("uri has no host");
         let port = req.uri().port_u16().unwrap_or(80);
         let addr = format!("{}:{}", host, port);
-        let stream = TcpStream::connect(addr).await.unwrap();
+
+        if !host_policy.is_allowed(host) {
+            eprintln!("request to {} rejected by host policy", host);
+            return Ok(forbidden());
+        }
+
+        if let Some(host_header) = req
+            .headers()
+            .get(hyper::header::HOST)
+            .and_then(|v| v.to_str().ok())
+        {
+            if !host.eq_ignore_ascii_case(host_only(host_header)) {
+                eprintln!(
+                    "rejecting request: URI host {} does not match Host header {}",
+                    host, host_header
+                );
+                return Ok(misdirected());
+            }
+        }
+
+        // Route through the configured parent proxy, if any; its absolute-form
+        // request line already tells it where to forward this on to.
+        let dial_addr = match upstream_proxy.as_ref() {
+            Some(parent) => &parent.addr,
+            None => &addr,
+        };
+        let sock_addr = match resolve_cached(&dns_cache, dial_addr).await {
+            Ok(sock_addr) => sock_addr,
+            Err(e) => {
+                eprintln!("failed to resolve {}: {}", dial_addr, e);
+                return Ok(bad_gateway());
+            }
+        };
+        let mut stream = match TcpStream::connect(sock_addr).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("failed to connect to {}: {}", dial_addr, e);
+                return Ok(bad_gateway());
+            }
+        };
+        if upstream_proxy.is_none() {
+            if let Some(version) = proxy_protocol {
+                if let Err(e) = write_proxy_protocol_header(&mut stream, version, client_addr, sock_addr).await {
+                    eprintln!("failed to write PROXY protocol header to {}: {}", addr, e);
+                    return Ok(bad_gateway());
+                }
+            }
+        }
         let io = TokioIo::new(stream);
 
         let (mut sender, conn) = hyper::client::conn::http1::handshake(io).await?;
@@ -116,8 +403,58 @@ async fn proxy(
             }
         });
 
-        let res = sender.send_request(req).await?;
-        Ok(res.map(|b| b.boxed()))
+        let mut req = req;
+        let accept_encoding = req
+            .headers()
+            .get(ACCEPT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        strip_hop_by_hop_headers(req.headers_mut());
+        append_forwarding_headers(req.headers_mut(), client_addr);
+        if let Some(parent) = upstream_proxy.as_ref() {
+            if let Some(auth) = &parent.auth {
+                req.headers_mut()
+                    .insert("Proxy-Authorization", auth.parse().unwrap());
+            }
+        }
+
+        let mut res = sender.send_request(req).await?;
+        strip_hop_by_hop_headers(res.headers_mut());
+        Ok(negotiate_content_encoding(res, accept_encoding.as_deref()))
+    }
+}
+
+fn is_authenticated(req: &Request<hyper::body::Incoming>, credentials: &AuthCredentials) -> bool {
+    let header = req
+        .headers()
+        .get("proxy-authorization")
+        .and_then(|h| h.to_str().ok());
+    check_authorization(header, credentials)
+}
+
+/// Core of [`is_authenticated`], taking the raw `Proxy-Authorization` header
+/// value directly so it can be unit tested without a real `Request`.
+fn check_authorization(header: Option<&str>, credentials: &AuthCredentials) -> bool {
+    let Some(header) = header else {
+        return false;
+    };
+
+    match credentials {
+        AuthCredentials::Basic { username, password } => header
+            .strip_prefix("Basic ")
+            .and_then(|encoded| general_purpose::STANDARD.decode(encoded).ok())
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .is_some_and(|decoded_str| {
+                let mut parts = decoded_str.splitn(2, ':');
+                if let (Some(req_user), Some(req_pass)) = (parts.next(), parts.next()) {
+                    req_user == username && req_pass == password
+                } else {
+                    false
+                }
+            }),
+        AuthCredentials::Bearer { token } => header
+            .strip_prefix("Bearer ")
+            .is_some_and(|provided| constant_time_eq(provided.as_bytes(), token.as_bytes())),
     }
 }
 
@@ -125,17 +462,723 @@ fn host_addr(uri: &Uri) -> Option<String> {
     uri.authority().map(|auth| auth.to_string())
 }
 
-fn empty_body() -> BoxBody<Bytes, hyper::Error> {
+/// Headers that are connection-scoped and must never be forwarded upstream or
+/// back to the client, per RFC 7230 section 6.1.
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Removes hop-by-hop headers: the standard set plus anything named in `Connection`.
+fn strip_hop_by_hop_headers(headers: &mut HeaderMap) {
+    let connection_named: Vec<String> = headers
+        .get(hyper::header::CONNECTION)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.split(',').map(|name| name.trim().to_ascii_lowercase()).collect())
+        .unwrap_or_default();
+
+    headers.remove(hyper::header::CONNECTION);
+    for name in HOP_BY_HOP_HEADERS {
+        headers.remove(*name);
+    }
+    for name in connection_named {
+        headers.remove(name.as_str());
+    }
+}
+
+/// Appends this hop to `X-Forwarded-For` and `Via` on a request bound upstream.
+fn append_forwarding_headers(headers: &mut HeaderMap, client_addr: SocketAddr) {
+    let client_ip = client_addr.ip().to_string();
+    let xff = match headers.get("X-Forwarded-For").and_then(|v| v.to_str().ok()) {
+        Some(existing) => format!("{}, {}", existing, client_ip),
+        None => client_ip,
+    };
+    headers.insert("X-Forwarded-For", xff.parse().unwrap());
+
+    let via = match headers.get("Via").and_then(|v| v.to_str().ok()) {
+        Some(existing) => format!("{}, 1.1 myproxy", existing),
+        None => "1.1 myproxy".to_string(),
+    };
+    headers.insert("Via", via.parse().unwrap());
+}
+
+/// Content-Encoding values this proxy knows how to produce and consume.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Gzip,
+    Brotli,
+}
+
+impl Encoding {
+    fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Brotli => "br",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<Self> {
+        match s.trim() {
+            "gzip" | "x-gzip" => Some(Encoding::Gzip),
+            "br" => Some(Encoding::Brotli),
+            _ => None,
+        }
+    }
+}
+
+/// Content-Type prefixes that are already compressed; re-encoding them wastes
+/// CPU for no size benefit, so they're passed through untouched.
+const PRECOMPRESSED_CONTENT_TYPES: &[&str] = &[
+    "image/", "video/", "audio/", "application/zip", "application/gzip",
+    "application/x-gzip", "application/x-brotli", "application/pdf",
+];
+
+fn is_precompressed_content_type(content_type: &str) -> bool {
+    let content_type = content_type.to_ascii_lowercase();
+    PRECOMPRESSED_CONTENT_TYPES
+        .iter()
+        .any(|prefix| content_type.starts_with(prefix))
+}
+
+/// Whether an `Accept-Encoding` entry carries a zero `q` weight (`;q=0`,
+/// `; q=0.0`, ...).
+fn has_zero_q(part: &str) -> bool {
+    part.split(';').skip(1).any(|param| {
+        let mut kv = param.splitn(2, '=');
+        let (Some(key), Some(value)) = (kv.next(), kv.next()) else {
+            return false;
+        };
+        key.trim().eq_ignore_ascii_case("q") && value.trim().parse::<f32>().is_ok_and(|q| q == 0.0)
+    })
+}
+
+/// Returns the client's most preferred encoding this proxy supports, if any,
+/// ignoring `q` weighting (any non-zero preference counts as accepted).
+fn preferred_client_encoding(accept_encoding: &str) -> Option<Encoding> {
+    accept_encoding
+        .split(',')
+        .filter_map(|part| {
+            let name = part.split(';').next().unwrap_or("").trim();
+            if has_zero_q(part) {
+                None
+            } else {
+                Encoding::from_str(name)
+            }
+        })
+        .next()
+}
+
+/// Whether `accept_encoding` lists `encoding` without explicitly excluding it
+/// via `;q=0`.
+fn accepts_encoding(accept_encoding: &str, encoding: Encoding) -> bool {
+    accept_encoding.split(',').any(|part| {
+        let name = part.split(';').next().unwrap_or("").trim();
+        Encoding::from_str(name) == Some(encoding) && !has_zero_q(part)
+    })
+}
+
+/// Re-encodes or decodes a forwarded response body so its `Content-Encoding`
+/// matches what the client asked for in `Accept-Encoding`, streaming the
+/// conversion rather than buffering the whole body in memory. Encodings other
+/// than gzip and br are passed through unchanged.
+fn negotiate_content_encoding(
+    res: Response<hyper::body::Incoming>,
+    accept_encoding: Option<&str>,
+) -> Response<ResBody> {
+    let content_type = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+    if is_precompressed_content_type(&content_type) {
+        return res.map(|b| b.map_err(|e| Box::new(e) as BodyError).boxed());
+    }
+
+    let upstream_is_identity = res
+        .headers()
+        .get(CONTENT_ENCODING)
+        .map_or(true, |v| v.as_bytes().eq_ignore_ascii_case(b"identity"));
+    let upstream_encoding = res
+        .headers()
+        .get(CONTENT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .and_then(Encoding::from_str);
+    let wanted_encoding = accept_encoding.and_then(preferred_client_encoding);
+
+    match (upstream_encoding, wanted_encoding) {
+        (None, Some(target)) if upstream_is_identity => {
+            // Upstream sent identity, the client wants compression: compress.
+            let (mut parts, body) = res.into_parts();
+            parts.headers.remove(CONTENT_LENGTH);
+            parts
+                .headers
+                .insert(CONTENT_ENCODING, target.as_str().parse().unwrap());
+            Response::from_parts(parts, compress_body(body, target))
+        }
+        (Some(current), _) if !accept_encoding.is_some_and(|ae| accepts_encoding(ae, current)) => {
+            // Upstream is compressed, the client never asked for it: decompress.
+            let (mut parts, body) = res.into_parts();
+            parts.headers.remove(CONTENT_LENGTH);
+            parts.headers.remove(CONTENT_ENCODING);
+            Response::from_parts(parts, decompress_body(body, current))
+        }
+        _ => res.map(|b| b.map_err(|e| Box::new(e) as BodyError).boxed()),
+    }
+}
+
+fn compress_body(body: hyper::body::Incoming, encoding: Encoding) -> ResBody {
+    let reader = StreamReader::new(
+        body.into_data_stream()
+            .map(|r| r.map_err(std::io::Error::other)),
+    );
+    let stream: std::pin::Pin<Box<dyn futures_util::Stream<Item = std::io::Result<Bytes>> + Send + Sync>> =
+        match encoding {
+            Encoding::Gzip => Box::pin(ReaderStream::new(GzipEncoder::new(reader))),
+            Encoding::Brotli => Box::pin(ReaderStream::new(BrotliEncoder::new(reader))),
+        };
+    BodyExt::boxed(StreamBody::new(stream.map(|r| r.map(Frame::data).map_err(|e| Box::new(e) as BodyError))))
+}
+
+fn decompress_body(body: hyper::body::Incoming, encoding: Encoding) -> ResBody {
+    let reader = StreamReader::new(
+        body.into_data_stream()
+            .map(|r| r.map_err(std::io::Error::other)),
+    );
+    let stream: std::pin::Pin<Box<dyn futures_util::Stream<Item = std::io::Result<Bytes>> + Send + Sync>> =
+        match encoding {
+            Encoding::Gzip => Box::pin(ReaderStream::new(GzipDecoder::new(reader))),
+            Encoding::Brotli => Box::pin(ReaderStream::new(BrotliDecoder::new(reader))),
+        };
+    BodyExt::boxed(StreamBody::new(stream.map(|r| r.map(Frame::data).map_err(|e| Box::new(e) as BodyError))))
+}
+
+fn empty_body() -> ResBody {
     Empty::<Bytes>::new().map_err(|e| match e {}).boxed()
 }
 
-fn full_body(chunk: &'static str) -> BoxBody<Bytes, hyper::Error> {
+fn full_body(chunk: &'static str) -> ResBody {
     Full::new(Bytes::from(chunk)).map_err(|e| match e {}).boxed()
 }
 
-async fn tunnel(upgraded: hyper::upgrade::Upgraded, addr: String) -> std::io::Result<()> {
-    let mut server = TcpStream::connect(addr).await?;
+fn bad_gateway() -> Response<ResBody> {
+    let mut res = Response::new(full_body("502 Bad Gateway"));
+    *res.status_mut() = StatusCode::BAD_GATEWAY;
+    res
+}
+
+fn forbidden() -> Response<ResBody> {
+    let mut res = Response::new(full_body("403 Forbidden"));
+    *res.status_mut() = StatusCode::FORBIDDEN;
+    res
+}
+
+fn misdirected() -> Response<ResBody> {
+    let mut res = Response::new(full_body("421 Misdirected Request"));
+    *res.status_mut() = StatusCode::MISDIRECTED_REQUEST;
+    res
+}
+
+async fn tunnel(
+    upgraded: hyper::upgrade::Upgraded,
+    addr: String,
+    dns_cache: DnsCacheHandle,
+    client_addr: SocketAddr,
+    proxy_protocol: Option<ProxyProtocolVersion>,
+    upstream_proxy: Arc<Option<UpstreamProxy>>,
+    host_policy: Arc<HostPolicy>,
+) -> std::io::Result<()> {
+    let mut server = match upstream_proxy.as_ref() {
+        Some(parent) => connect_via_upstream_proxy(&dns_cache, parent, &addr).await?,
+        None => {
+            let sock_addr = resolve_cached(&dns_cache, &addr)
+                .await
+                .map_err(|e| std::io::Error::new(e.kind(), format!("failed to resolve {}: {}", addr, e)))?;
+            let mut server = TcpStream::connect(sock_addr).await?;
+            if let Some(version) = proxy_protocol {
+                write_proxy_protocol_header(&mut server, version, client_addr, sock_addr).await?;
+            }
+            server
+        }
+    };
     let mut upgraded = TokioIo::new(upgraded);
+
+    // The CONNECT authority only tells us where the client SAID it wanted to
+    // go; domain fronting is a client that CONNECTs to an allowed host and
+    // then hands a different, blocked hostname to the origin via the TLS SNI
+    // inside the tunnel, which a plain copy_bidirectional would forward
+    // blind. Peek the client's first flight and, if it parses as a TLS
+    // ClientHello, require its SNI to match the CONNECT authority and pass
+    // the host policy before forwarding anything. Traffic that isn't TLS at
+    // all is passed through untouched -- there's no SNI to front with --
+    // but a genuine ClientHello is accumulated across as many reads as it
+    // takes to collect the whole record, since a client fronting on purpose
+    // can split it across writes to slip a truncated peek past a one-shot read.
+    let mut peeked = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        if peeked.len() >= TLS_CLIENT_HELLO_PEEK_LIMIT {
+            break;
+        }
+        let n = upgraded.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        peeked.extend_from_slice(&chunk[..n]);
+        match tls_record_len(&peeked) {
+            Some(0) => break,                                  // not a TLS handshake record at all
+            Some(total) if peeked.len() >= total => break,     // full record in hand
+            _ => {}                                            // need more header or body bytes
+        }
+    }
+    if let Some(sni) = parse_client_hello_sni(&peeked) {
+        let authority_host = host_only(&addr);
+        if !sni.eq_ignore_ascii_case(authority_host) || !host_policy.is_allowed(&sni) {
+            return Err(std::io::Error::other(format!(
+                "rejecting CONNECT tunnel to {}: TLS SNI {} does not match the allowed authority (domain fronting)",
+                addr, sni
+            )));
+        }
+    }
+    if !peeked.is_empty() {
+        server.write_all(&peeked).await?;
+    }
+
     tokio::io::copy_bidirectional(&mut upgraded, &mut server).await?;
     Ok(())
+}
+
+/// Upper bound on how many bytes of the client's first flight we'll buffer
+/// while looking for a TLS ClientHello's SNI extension: the largest TLS
+/// record, a 5-byte header plus a 2^14-byte body.
+const TLS_CLIENT_HELLO_PEEK_LIMIT: usize = 5 + 16 * 1024;
+
+/// Reads a TLS record header (content type + 2-byte version + 2-byte
+/// big-endian length) off the front of `buf` and returns the total record
+/// size including that 5-byte header. Returns `Some(0)` as a sentinel when
+/// `buf` already has enough bytes to know it isn't a handshake record at
+/// all, and `None` when there aren't even enough bytes to tell yet.
+fn tls_record_len(buf: &[u8]) -> Option<usize> {
+    if buf.len() < 5 {
+        return None;
+    }
+    if buf[0] != 0x16 {
+        return Some(0);
+    }
+    let record_len = u16::from_be_bytes([buf[3], buf[4]]) as usize;
+    Some(5 + record_len)
+}
+
+/// Extracts the `server_name` extension from a TLS ClientHello, if `buf`
+/// contains one complete handshake record. Returns `None` both when `buf`
+/// isn't a (complete) ClientHello and when it is one with no SNI extension
+/// -- callers don't need to tell those apart, since either way there's
+/// nothing to check against the CONNECT authority.
+fn parse_client_hello_sni(buf: &[u8]) -> Option<String> {
+    let total_len = tls_record_len(buf).filter(|&total| total > 0)?;
+    let record = buf.get(5..total_len)?;
+
+    // Handshake header: type (0x01 = ClientHello), length (3 bytes).
+    if record.len() < 4 || record[0] != 0x01 {
+        return None;
+    }
+    let hs_len = u32::from_be_bytes([0, record[1], record[2], record[3]]) as usize;
+    let body = record.get(4..4 + hs_len)?;
+
+    // legacy_version(2) + random(32) + session_id
+    let mut pos = 34;
+    let session_id_len = *body.get(pos)? as usize;
+    pos += 1 + session_id_len;
+
+    let cipher_suites_len = u16::from_be_bytes([*body.get(pos)?, *body.get(pos + 1)?]) as usize;
+    pos += 2 + cipher_suites_len;
+
+    let compression_methods_len = *body.get(pos)? as usize;
+    pos += 1 + compression_methods_len;
+
+    let extensions_len = u16::from_be_bytes([*body.get(pos)?, *body.get(pos + 1)?]) as usize;
+    pos += 2;
+    let extensions = body.get(pos..pos + extensions_len)?;
+
+    let mut pos = 0;
+    while pos + 4 <= extensions.len() {
+        let ext_type = u16::from_be_bytes([extensions[pos], extensions[pos + 1]]);
+        let ext_len = u16::from_be_bytes([extensions[pos + 2], extensions[pos + 3]]) as usize;
+        pos += 4;
+        let ext_data = extensions.get(pos..pos + ext_len)?;
+        pos += ext_len;
+
+        if ext_type != 0x0000 {
+            continue;
+        }
+        // server_name_list: list_len(2) + entries of [name_type(1), name_len(2), name]
+        let list_len = u16::from_be_bytes([*ext_data.first()?, *ext_data.get(1)?]) as usize;
+        let list = ext_data.get(2..2 + list_len)?;
+        let mut lpos = 0;
+        while lpos + 3 <= list.len() {
+            let name_type = list[lpos];
+            let name_len = u16::from_be_bytes([list[lpos + 1], list[lpos + 2]]) as usize;
+            lpos += 3;
+            let name = list.get(lpos..lpos + name_len)?;
+            lpos += name_len;
+            if name_type == 0 {
+                return std::str::from_utf8(name).ok().map(str::to_string);
+            }
+        }
+    }
+    None
+}
+
+/// Opens a tunnel to `target` through the configured parent proxy via `CONNECT`,
+/// verifying the parent grants it before handing the socket back.
+async fn connect_via_upstream_proxy(
+    dns_cache: &DnsCacheHandle,
+    parent: &UpstreamProxy,
+    target: &str,
+) -> std::io::Result<TcpStream> {
+    let parent_addr = resolve_cached(dns_cache, &parent.addr).await.map_err(|e| {
+        std::io::Error::new(e.kind(), format!("failed to resolve upstream proxy {}: {}", parent.addr, e))
+    })?;
+    let mut stream = TcpStream::connect(parent_addr).await?;
+
+    let mut request = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n");
+    if let Some(auth) = &parent.auth {
+        request.push_str(&format!("Proxy-Authorization: {auth}\r\n"));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    {
+        let mut reader = BufReader::new(&mut stream);
+        let mut status_line = String::new();
+        reader.read_line(&mut status_line).await?;
+        if !status_line.contains(" 200 ") {
+            return Err(std::io::Error::other(format!(
+                "upstream proxy CONNECT to {} failed: {}",
+                target,
+                status_line.trim()
+            )));
+        }
+        loop {
+            let mut line = String::new();
+            let n = reader.read_line(&mut line).await?;
+            if n == 0 || line == "\r\n" || line == "\n" {
+                break;
+            }
+        }
+    }
+
+    Ok(stream)
+}
+
+/// Writes a PROXY protocol (v1 or v2) header describing `client_addr` -> `dest_addr`
+/// onto a freshly opened upstream connection, before any proxied bytes flow.
+async fn write_proxy_protocol_header(
+    stream: &mut TcpStream,
+    version: ProxyProtocolVersion,
+    client_addr: SocketAddr,
+    dest_addr: SocketAddr,
+) -> std::io::Result<()> {
+    let header = match version {
+        ProxyProtocolVersion::V1 => proxy_protocol_v1(client_addr, dest_addr).into_bytes(),
+        ProxyProtocolVersion::V2 => proxy_protocol_v2(client_addr, dest_addr),
+    };
+    stream.write_all(&header).await
+}
+
+fn proxy_protocol_v1(client_addr: SocketAddr, dest_addr: SocketAddr) -> String {
+    match (client_addr, dest_addr) {
+        (SocketAddr::V4(client), SocketAddr::V4(dest)) => format!(
+            "PROXY TCP4 {} {} {} {}\r\n",
+            client.ip(),
+            dest.ip(),
+            client.port(),
+            dest.port()
+        ),
+        (SocketAddr::V6(client), SocketAddr::V6(dest)) => format!(
+            "PROXY TCP6 {} {} {} {}\r\n",
+            client.ip(),
+            dest.ip(),
+            client.port(),
+            dest.port()
+        ),
+        _ => "PROXY UNKNOWN\r\n".to_string(),
+    }
+}
+
+const PROXY_PROTOCOL_V2_SIGNATURE: [u8; 12] = [
+    0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+
+fn proxy_protocol_v2(client_addr: SocketAddr, dest_addr: SocketAddr) -> Vec<u8> {
+    let mut header = Vec::with_capacity(28);
+    header.extend_from_slice(&PROXY_PROTOCOL_V2_SIGNATURE);
+    header.push(0x21); // version 2, command PROXY
+
+    match (client_addr, dest_addr) {
+        (SocketAddr::V4(client), SocketAddr::V4(dest)) => {
+            header.push(0x11); // AF_INET, STREAM
+            header.extend_from_slice(&12u16.to_be_bytes());
+            header.extend_from_slice(&client.ip().octets());
+            header.extend_from_slice(&dest.ip().octets());
+            header.extend_from_slice(&client.port().to_be_bytes());
+            header.extend_from_slice(&dest.port().to_be_bytes());
+        }
+        (SocketAddr::V6(client), SocketAddr::V6(dest)) => {
+            header.push(0x21); // AF_INET6, STREAM
+            header.extend_from_slice(&36u16.to_be_bytes());
+            header.extend_from_slice(&client.ip().octets());
+            header.extend_from_slice(&dest.ip().octets());
+            header.extend_from_slice(&client.port().to_be_bytes());
+            header.extend_from_slice(&dest.port().to_be_bytes());
+        }
+        _ => {
+            header.push(0x00); // AF_UNSPEC, UNSPEC
+            header.extend_from_slice(&0u16.to_be_bytes());
+        }
+    }
+
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn dns_cache_expires_entries_past_their_ttl() {
+        let mut cache = DnsCache::new(10, Duration::from_millis(10));
+        cache.insert("example.com:80".to_string(), addr(1));
+        assert_eq!(cache.get("example.com:80"), Some(addr(1)));
+
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(cache.get("example.com:80"), None);
+    }
+
+    #[test]
+    fn dns_cache_get_hit_protects_an_entry_from_eviction() {
+        let mut cache = DnsCache::new(2, Duration::from_secs(60));
+        cache.insert("a".to_string(), addr(1));
+        cache.insert("b".to_string(), addr(2));
+
+        // Touch "a" so it's no longer the least-recently-used entry.
+        assert_eq!(cache.get("a"), Some(addr(1)));
+
+        // Inserting past capacity should evict "b" (untouched since insert),
+        // not "a" (touched more recently by the get above).
+        cache.insert("c".to_string(), addr(3));
+        assert_eq!(cache.get("a"), Some(addr(1)));
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("c"), Some(addr(3)));
+    }
+
+    #[test]
+    fn constant_time_eq_compares_equal_and_unequal_slices() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"wrong!"));
+        assert!(!constant_time_eq(b"secret", b"short"));
+    }
+
+    #[test]
+    fn check_authorization_accepts_matching_bearer_token() {
+        let credentials = AuthCredentials::Bearer { token: "t0ken".to_string() };
+        assert!(check_authorization(Some("Bearer t0ken"), &credentials));
+        assert!(!check_authorization(Some("Bearer wrong"), &credentials));
+        assert!(!check_authorization(None, &credentials));
+    }
+
+    #[test]
+    fn check_authorization_accepts_matching_basic_credentials() {
+        let credentials = AuthCredentials::Basic {
+            username: "alice".to_string(),
+            password: "hunter2".to_string(),
+        };
+        let header = format!(
+            "Basic {}",
+            general_purpose::STANDARD.encode("alice:hunter2")
+        );
+        assert!(check_authorization(Some(&header), &credentials));
+
+        let wrong_header = format!("Basic {}", general_purpose::STANDARD.encode("alice:wrong"));
+        assert!(!check_authorization(Some(&wrong_header), &credentials));
+    }
+
+    #[test]
+    fn glob_match_supports_wildcards_and_is_case_insensitive() {
+        assert!(glob_match("*.example.com", "api.example.com"));
+        assert!(glob_match("*.example.com", "API.EXAMPLE.COM"));
+        assert!(!glob_match("*.example.com", "example.com"));
+        assert!(glob_match("exact.host", "exact.host"));
+        assert!(glob_match("ho?t", "host"));
+        assert!(!glob_match("ho?t", "hoost"));
+    }
+
+    #[test]
+    fn proxy_protocol_v1_formats_tcp4_and_tcp6_lines() {
+        let client: SocketAddr = "10.0.0.1:1234".parse().unwrap();
+        let dest: SocketAddr = "10.0.0.2:443".parse().unwrap();
+        assert_eq!(proxy_protocol_v1(client, dest), "PROXY TCP4 10.0.0.1 10.0.0.2 1234 443\r\n");
+
+        let client6: SocketAddr = "[::1]:1234".parse().unwrap();
+        let dest6: SocketAddr = "[::2]:443".parse().unwrap();
+        assert_eq!(proxy_protocol_v1(client6, dest6), "PROXY TCP6 ::1 ::2 1234 443\r\n");
+    }
+
+    #[test]
+    fn proxy_protocol_v1_falls_back_to_unknown_for_mixed_families() {
+        let client: SocketAddr = "10.0.0.1:1234".parse().unwrap();
+        let dest6: SocketAddr = "[::2]:443".parse().unwrap();
+        assert_eq!(proxy_protocol_v1(client, dest6), "PROXY UNKNOWN\r\n");
+    }
+
+    #[test]
+    fn proxy_protocol_v2_encodes_tcp4_header() {
+        let client: SocketAddr = "10.0.0.1:1234".parse().unwrap();
+        let dest: SocketAddr = "10.0.0.2:443".parse().unwrap();
+        let header = proxy_protocol_v2(client, dest);
+
+        assert_eq!(&header[0..12], &PROXY_PROTOCOL_V2_SIGNATURE);
+        assert_eq!(header[12], 0x21); // version 2, command PROXY
+        assert_eq!(header[13], 0x11); // AF_INET, STREAM
+        assert_eq!(u16::from_be_bytes([header[14], header[15]]), 12);
+        assert_eq!(&header[16..20], &[10, 0, 0, 1]);
+        assert_eq!(&header[20..24], &[10, 0, 0, 2]);
+        assert_eq!(u16::from_be_bytes([header[24], header[25]]), 1234);
+        assert_eq!(u16::from_be_bytes([header[26], header[27]]), 443);
+    }
+
+    #[test]
+    fn proxy_protocol_v2_encodes_unspec_for_mixed_families() {
+        let client: SocketAddr = "10.0.0.1:1234".parse().unwrap();
+        let dest6: SocketAddr = "[::2]:443".parse().unwrap();
+        let header = proxy_protocol_v2(client, dest6);
+
+        assert_eq!(header[13], 0x00); // AF_UNSPEC, UNSPEC
+        assert_eq!(u16::from_be_bytes([header[14], header[15]]), 0);
+        assert_eq!(header.len(), 16);
+    }
+
+    #[test]
+    fn host_policy_deny_overrides_allow() {
+        let policy = HostPolicy {
+            allow: vec!["*.example.com".to_string()],
+            deny: vec!["blocked.example.com".to_string()],
+        };
+        assert!(policy.is_allowed("api.example.com"));
+        assert!(!policy.is_allowed("blocked.example.com"));
+    }
+
+    #[test]
+    fn host_policy_empty_allow_permits_everything_not_denied() {
+        let policy = HostPolicy {
+            allow: vec![],
+            deny: vec!["blocked.example.com".to_string()],
+        };
+        assert!(policy.is_allowed("anything.example.com"));
+        assert!(!policy.is_allowed("blocked.example.com"));
+    }
+
+    #[test]
+    fn host_policy_rejects_hosts_not_matching_a_non_empty_allow_list() {
+        let policy = HostPolicy {
+            allow: vec!["*.example.com".to_string()],
+            deny: vec![],
+        };
+        assert!(policy.is_allowed("api.example.com"));
+        assert!(!policy.is_allowed("api.other.com"));
+    }
+
+    #[test]
+    fn accepts_encoding_respects_q0_exclusion() {
+        assert!(!accepts_encoding("gzip;q=0, identity", Encoding::Gzip));
+        assert!(accepts_encoding("gzip;q=0.5, br", Encoding::Gzip));
+        assert!(accepts_encoding("gzip", Encoding::Gzip));
+        assert!(!accepts_encoding("br", Encoding::Gzip));
+    }
+
+    /// Builds a minimal TLS 1.2 ClientHello record carrying a single SNI
+    /// host name, as produced by a real client's first flight.
+    fn client_hello_with_sni(server_name: &str) -> Vec<u8> {
+        let mut server_name_list = Vec::new();
+        server_name_list.push(0u8); // name_type: host_name
+        server_name_list.extend_from_slice(&(server_name.len() as u16).to_be_bytes());
+        server_name_list.extend_from_slice(server_name.as_bytes());
+
+        let mut sni_extension_data = Vec::new();
+        sni_extension_data.extend_from_slice(&(server_name_list.len() as u16).to_be_bytes());
+        sni_extension_data.extend_from_slice(&server_name_list);
+
+        let mut extensions = Vec::new();
+        extensions.extend_from_slice(&0x0000u16.to_be_bytes()); // extension type: server_name
+        extensions.extend_from_slice(&(sni_extension_data.len() as u16).to_be_bytes());
+        extensions.extend_from_slice(&sni_extension_data);
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&[0x03, 0x03]); // legacy_version: TLS 1.2
+        body.extend_from_slice(&[0u8; 32]); // random
+        body.push(0); // session_id_len
+        body.extend_from_slice(&2u16.to_be_bytes()); // cipher_suites_len (one 2-byte suite)
+        body.extend_from_slice(&[0x00, 0x00]); // one cipher suite
+        body.push(1); // compression_methods_len
+        body.push(0); // compression method: null
+        body.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        body.extend_from_slice(&extensions);
+
+        let mut handshake = Vec::new();
+        handshake.push(0x01); // ClientHello
+        let body_len = body.len() as u32;
+        handshake.extend_from_slice(&body_len.to_be_bytes()[1..]);
+        handshake.extend_from_slice(&body);
+
+        let mut record = Vec::new();
+        record.push(0x16); // handshake content type
+        record.extend_from_slice(&[0x03, 0x01]); // record version
+        record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+        record.extend_from_slice(&handshake);
+        record
+    }
+
+    #[test]
+    fn parse_client_hello_sni_extracts_server_name() {
+        let record = client_hello_with_sni("example.com");
+        assert_eq!(parse_client_hello_sni(&record).as_deref(), Some("example.com"));
+    }
+
+    #[test]
+    fn parse_client_hello_sni_ignores_non_tls_traffic() {
+        assert_eq!(parse_client_hello_sni(b"GET / HTTP/1.1\r\n"), None);
+        assert_eq!(parse_client_hello_sni(&[0x16, 0x03, 0x01]), None);
+    }
+
+    #[test]
+    fn parse_client_hello_sni_handles_a_record_reassembled_from_fragments() {
+        // A client (or an adversary deliberately evading a one-shot peek)
+        // can split its ClientHello across several TCP writes; the record
+        // should parse once all its bytes have been accumulated.
+        let record = client_hello_with_sni("example.com");
+        let (first, rest) = record.split_at(1);
+        let mut reassembled = first.to_vec();
+        reassembled.extend_from_slice(rest);
+        assert_eq!(parse_client_hello_sni(&reassembled).as_deref(), Some("example.com"));
+    }
+
+    #[test]
+    fn tls_record_len_reports_pending_until_the_full_record_is_available() {
+        let record = client_hello_with_sni("example.com");
+        assert_eq!(tls_record_len(&record[..4]), None); // header itself incomplete
+        let total = tls_record_len(&record).unwrap();
+        assert!(total > 5);
+        assert_eq!(tls_record_len(&record[..total - 1]), Some(total)); // body still incomplete
+        assert_eq!(tls_record_len(&record[..total]), Some(total)); // complete
+        assert_eq!(tls_record_len(b"GET / HTTP/1.1\r\n"), Some(0)); // not a handshake record
+    }
 }
\ No newline at end of file